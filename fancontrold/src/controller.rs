@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Tunable coefficients and output bounds for a [`Controller`].
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Parameters {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+
+    /// Bounds the final output of the controller.
+    pub output_min: f32,
+    pub output_max: f32,
+
+    /// Bounds the accumulated integral term, to prevent windup.
+    pub integral_min: f32,
+    pub integral_max: f32,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            kp: 0.05,
+            ki: 0.01,
+            kd: 0.0,
+            output_min: 0.0,
+            output_max: 1.0,
+            integral_min: 0.0,
+            integral_max: 20.0,
+        }
+    }
+}
+
+impl Parameters {
+    pub fn validated(self) -> Result<Self, ParametersError> {
+        if self.output_min > self.output_max {
+            return Err(ParametersError::InvalidRange {
+                name: "output",
+                min: self.output_min,
+                max: self.output_max,
+            });
+        }
+
+        if self.integral_min > self.integral_max {
+            return Err(ParametersError::InvalidRange {
+                name: "integral",
+                min: self.integral_min,
+                max: self.integral_max,
+            });
+        }
+
+        if !(0.0..=1.0).contains(&self.output_min) || !(0.0..=1.0).contains(&self.output_max) {
+            return Err(ParametersError::OutputOutOfDutyRange {
+                output_min: self.output_min,
+                output_max: self.output_max,
+            });
+        }
+
+        Ok(self)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParametersError {
+    #[error("`{name}_min` must not be greater than `{name}_max`, but is {min} and {max} respectively")]
+    InvalidRange {
+        name: &'static str,
+        min: f32,
+        max: f32,
+    },
+    #[error("`output_min`/`output_max` must fall within 0.0..=1.0 to map directly onto PWM duty cycle, but are {output_min} and {output_max} respectively")]
+    OutputOutOfDutyRange { output_min: f32, output_max: f32 },
+}
+
+/// A PID controller that drives fan PWM duty cycle to hold a target
+/// temperature, instead of stepping through discrete thresholds.
+///
+/// Note the sign convention is inverted relative to a heater control loop: a
+/// higher temperature must *raise* the output (fan speed), not lower it. This
+/// is handled by computing the error as `input - target` rather than the
+/// usual `target - input`.
+#[derive(Debug)]
+pub struct Controller {
+    parameters: Parameters,
+    target: f32,
+    integral: f32,
+    last_input: Option<f32>,
+}
+
+impl Controller {
+    pub fn new(parameters: Parameters, target: f32) -> Self {
+        Self {
+            parameters,
+            target,
+            integral: 0.0,
+            last_input: None,
+        }
+    }
+
+    /// Feeds a new temperature reading and returns the clamped output, ready
+    /// to be used directly as a PWM duty cycle when `output_min`/`output_max`
+    /// are within `0.0..=1.0`.
+    pub fn update(&mut self, input: f32) -> f32 {
+        let Parameters {
+            kp,
+            ki,
+            kd,
+            output_min,
+            output_max,
+            integral_min,
+            integral_max,
+        } = self.parameters;
+
+        let error = input - self.target;
+
+        self.integral = (self.integral + error).clamp(integral_min, integral_max);
+
+        let derivative = self.last_input.map_or(0.0, |last_input| input - last_input);
+        self.last_input = Some(input);
+
+        (kp * error + ki * self.integral + kd * derivative).clamp(output_min, output_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validated_rejects_inverted_ranges() {
+        let params = Parameters {
+            output_min: 1.0,
+            output_max: 0.0,
+            ..Parameters::default()
+        };
+        assert!(params.validated().is_err());
+
+        let params = Parameters {
+            integral_min: 1.0,
+            integral_max: 0.0,
+            ..Parameters::default()
+        };
+        assert!(params.validated().is_err());
+    }
+
+    #[test]
+    fn validated_rejects_output_bounds_outside_duty_range() {
+        let params = Parameters {
+            output_max: 5.0,
+            ..Parameters::default()
+        };
+        assert!(params.validated().is_err());
+    }
+
+    #[test]
+    fn validated_accepts_defaults() {
+        assert!(Parameters::default().validated().is_ok());
+    }
+
+    #[test]
+    fn rises_output_when_above_target() {
+        let params = Parameters {
+            kp: 0.1,
+            ki: 0.0,
+            kd: 0.0,
+            ..Parameters::default()
+        };
+        let mut controller = Controller::new(params, 60.0);
+
+        // Above target: a fan must spin up, not down.
+        assert!(controller.update(70.0) > 0.0);
+    }
+
+    #[test]
+    fn clamps_output_to_bounds() {
+        let params = Parameters {
+            kp: 10.0,
+            ki: 0.0,
+            kd: 0.0,
+            ..Parameters::default()
+        };
+        let mut controller = Controller::new(params, 0.0);
+
+        assert_eq!(controller.update(100.0), params.output_max);
+    }
+
+    #[test]
+    fn integral_windup_is_clamped() {
+        let params = Parameters {
+            kp: 0.0,
+            ki: 1.0,
+            kd: 0.0,
+            integral_min: 0.0,
+            integral_max: 5.0,
+            ..Parameters::default()
+        };
+        let mut controller = Controller::new(params, 0.0);
+
+        for _ in 0..100 {
+            controller.update(10.0);
+        }
+
+        // ki * integral, with integral clamped to 5.0, clamped again to output_max.
+        assert_eq!(controller.update(10.0), params.output_max);
+    }
+
+    #[test]
+    fn derivative_rises_output_when_temperature_is_climbing() {
+        let params = Parameters {
+            kp: 0.0,
+            ki: 0.0,
+            kd: 1.0,
+            output_min: -10.0,
+            output_max: 10.0,
+            ..Parameters::default()
+        };
+        let mut controller = Controller::new(params, 1000.0);
+
+        controller.update(50.0);
+        // Temperature climbed since the last reading: with the fan's
+        // inverted error convention this must push output up, not down.
+        assert!(controller.update(60.0) > 0.0);
+    }
+}
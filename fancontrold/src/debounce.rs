@@ -0,0 +1,74 @@
+/// Debounces a stream of values, only accepting a candidate once it has been
+/// observed for a required number of consecutive calls. The streak resets
+/// whenever the observed value changes, so a transient spike back to the
+/// previous value is ignored rather than confirmed.
+pub struct Debounce<T> {
+    required_samples: u32,
+    candidate: Option<T>,
+    streak: u32,
+}
+
+impl<T: PartialEq + Clone> Debounce<T> {
+    pub fn new(required_samples: u32) -> Self {
+        Self {
+            required_samples: required_samples.max(1),
+            candidate: None,
+            streak: 0,
+        }
+    }
+
+    /// Feeds the latest observed value, returning `Some(value)` once it has
+    /// been observed for `required_samples` consecutive calls, or `None`
+    /// while the streak is still building.
+    pub fn observe(&mut self, value: T) -> Option<T> {
+        match &self.candidate {
+            Some(candidate) if *candidate == value => self.streak += 1,
+            _ => {
+                self.candidate = Some(value.clone());
+                self.streak = 1;
+            }
+        }
+
+        if self.streak >= self.required_samples {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirms_once_streak_reaches_required_samples() {
+        let mut debounce = Debounce::new(3);
+
+        assert_eq!(debounce.observe(1), None);
+        assert_eq!(debounce.observe(1), None);
+        assert_eq!(debounce.observe(1), Some(1));
+        // Stays confirmed as long as the value keeps holding.
+        assert_eq!(debounce.observe(1), Some(1));
+    }
+
+    #[test]
+    fn resets_streak_when_value_changes() {
+        let mut debounce = Debounce::new(3);
+
+        assert_eq!(debounce.observe(1), None);
+        assert_eq!(debounce.observe(1), None);
+        // A single differing reading resets the streak, even right before
+        // it would have been confirmed.
+        assert_eq!(debounce.observe(2), None);
+        assert_eq!(debounce.observe(2), None);
+        assert_eq!(debounce.observe(2), Some(2));
+    }
+
+    #[test]
+    fn zero_required_samples_confirms_immediately() {
+        let mut debounce = Debounce::new(0);
+
+        assert_eq!(debounce.observe(1), Some(1));
+    }
+}
@@ -0,0 +1,206 @@
+use std::{path::PathBuf, process::Command};
+
+use rppal::gpio::Gpio;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Software PWM frequency used to drive the fan.
+const PWM_FREQUENCY_HZ: f64 = 100.0;
+
+/// Anything that can be driven as a PWM fan.
+pub trait FanControl {
+    fn set_speed(&mut self, duty_cycle: f64) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn on(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_speed(1.0)
+    }
+
+    fn off(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// Anything that can produce a temperature reading.
+pub trait TempSource {
+    fn read(&mut self) -> Result<f32, Box<dyn std::error::Error>>;
+}
+
+/// Drives the fan over a GPIO pin using software PWM.
+pub struct GpioFan {
+    pin: rppal::gpio::OutputPin,
+}
+
+impl GpioFan {
+    pub fn new(gpio_pin: u8) -> Result<Self, rppal::gpio::Error> {
+        Ok(Self {
+            pin: Gpio::new()?.get(gpio_pin)?.into_output(),
+        })
+    }
+}
+
+impl FanControl for GpioFan {
+    fn set_speed(&mut self, duty_cycle: f64) -> Result<(), Box<dyn std::error::Error>> {
+        self.pin.set_pwm_frequency(PWM_FREQUENCY_HZ, duty_cycle)?;
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.pin.clear_pwm()?;
+        Ok(())
+    }
+}
+
+/// Reads the cpu temperature via `vcgencmd measure_temp`.
+pub struct VcgencmdSensor;
+
+impl TempSource for VcgencmdSensor {
+    fn read(&mut self) -> Result<f32, Box<dyn std::error::Error>> {
+        let output = Command::new("vcgencmd").arg("measure_temp").output()?;
+        let output = String::from_utf8_lossy(&output.stdout);
+
+        let temp_str = output
+            .strip_prefix("temp=")
+            .and_then(|s| s.strip_suffix("'C\n"))
+            .ok_or_else(|| ReadTempError::ParseError(output.clone().into_owned()))?;
+
+        temp_str
+            .parse()
+            .map_err(|_| ReadTempError::ParseError(output.into_owned()).into())
+    }
+}
+
+/// Reads a sysfs thermal zone node, which reports the temperature as an
+/// integer number of millidegrees Celsius.
+pub struct ThermalZoneSensor {
+    path: PathBuf,
+}
+
+impl ThermalZoneSensor {
+    pub fn new(zone: u32) -> Self {
+        Self {
+            path: PathBuf::from(format!("/sys/class/thermal/thermal_zone{zone}/temp")),
+        }
+    }
+}
+
+impl TempSource for ThermalZoneSensor {
+    fn read(&mut self) -> Result<f32, Box<dyn std::error::Error>> {
+        let raw = std::fs::read_to_string(&self.path)?;
+        let millidegrees: i32 = raw
+            .trim()
+            .parse()
+            .map_err(|_| ReadTempError::ThermalZoneParseError(raw.clone()))?;
+
+        Ok(millidegrees as f32 / 1000.0)
+    }
+}
+
+#[derive(Error, Debug)]
+enum ReadTempError {
+    #[error("expected format is `temp=<num>'C\\n`, instead is `{0}`")]
+    ParseError(String),
+    #[error("expected an integer number of millidegrees Celsius, instead is `{0}`")]
+    ThermalZoneParseError(String),
+}
+
+/// Combines several temperature readings into the single value fed into the
+/// control loop.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    #[default]
+    Max,
+    Average,
+}
+
+impl Aggregation {
+    fn aggregate(self, readings: &[f32]) -> f32 {
+        match self {
+            Aggregation::Max => readings.iter().copied().fold(f32::MIN, f32::max),
+            Aggregation::Average => readings.iter().sum::<f32>() / readings.len() as f32,
+        }
+    }
+}
+
+/// Reads several [`TempSource`]s and combines them via an [`Aggregation`].
+pub struct AggregateSensor {
+    sources: Vec<Box<dyn TempSource>>,
+    aggregation: Aggregation,
+}
+
+impl AggregateSensor {
+    pub fn new(sources: Vec<Box<dyn TempSource>>, aggregation: Aggregation) -> Self {
+        Self {
+            sources,
+            aggregation,
+        }
+    }
+}
+
+impl TempSource for AggregateSensor {
+    fn read(&mut self) -> Result<f32, Box<dyn std::error::Error>> {
+        let readings = self
+            .sources
+            .iter_mut()
+            .map(|source| source.read())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(self.aggregation.aggregate(&readings))
+    }
+}
+
+/// A no-op fan that just logs what it would do, for dev mode.
+pub struct FakeFan;
+
+impl FanControl for FakeFan {
+    fn set_speed(&mut self, duty_cycle: f64) -> Result<(), Box<dyn std::error::Error>> {
+        println!("[dev] fan duty cycle -> {:.0}%", duty_cycle * 100.0);
+        Ok(())
+    }
+
+    fn off(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        println!("[dev] fan -> off");
+        Ok(())
+    }
+}
+
+/// Plays back a scripted list of temperature readings, repeating the last one
+/// once exhausted, for dev mode.
+pub struct FakeSensor {
+    readings: std::vec::IntoIter<f32>,
+    last: f32,
+}
+
+impl FakeSensor {
+    pub fn new(readings: Vec<f32>) -> Self {
+        Self {
+            readings: readings.into_iter(),
+            last: 40.0,
+        }
+    }
+}
+
+impl TempSource for FakeSensor {
+    fn read(&mut self) -> Result<f32, Box<dyn std::error::Error>> {
+        if let Some(temp) = self.readings.next() {
+            self.last = temp;
+        }
+
+        println!("[dev] temperature reading -> {}'C", self.last);
+
+        Ok(self.last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregation_max_picks_the_highest_reading() {
+        assert_eq!(Aggregation::Max.aggregate(&[40.0, 70.0, 55.0]), 70.0);
+    }
+
+    #[test]
+    fn aggregation_average_of_readings() {
+        assert_eq!(Aggregation::Average.aggregate(&[40.0, 60.0]), 50.0);
+    }
+}
@@ -1,11 +1,23 @@
+mod controller;
+mod debounce;
+mod hardware;
+mod thermal;
+
 use std::{process::Command, time::Duration};
 
 use confy::ConfyError;
-use rppal::gpio::Gpio;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::time;
 
+use controller::{Controller, Parameters, ParametersError};
+use debounce::Debounce;
+use hardware::{
+    Aggregation, AggregateSensor, FakeFan, FakeSensor, FanControl, GpioFan, TempSource,
+    ThermalZoneSensor, VcgencmdSensor,
+};
+use thermal::{ThermalState, ThermalStateMachine};
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
     /// The gpio pin to which the fan is connected.
@@ -14,11 +26,108 @@ struct Config {
     /// The interval duration in seconds (int) to check the temperature.
     interval: u64,
 
-    /// The temperature passes which the fan is turned on.
-    on_threshold: f32,
+    /// How the fan duty cycle is derived from the current temperature.
+    control_mode: ControlMode,
+
+    /// The temperature sources to read each tick.
+    temp_sources: Vec<TempSourceConfig>,
+
+    /// How `temp_sources` are combined into the single reading fed into the
+    /// control loop.
+    temp_aggregation: Aggregation,
+
+    /// The temperature above which, once sustained for `critical_samples`
+    /// consecutive ticks, `critical_action` is run as a last-ditch safety
+    /// backstop.
+    critical_threshold: f32,
+
+    /// The temperature below which the critical alarm is deasserted again.
+    critical_recovery_threshold: f32,
+
+    /// How many consecutive samples above `critical_threshold` are required
+    /// before firing `critical_action`.
+    critical_samples: u32,
+
+    /// Shell command run (split on whitespace, no shell involved) when the
+    /// critical alarm fires, e.g. a shutdown command. Empty disables it.
+    critical_action: String,
+
+    /// When enabled, runs against a fake fan and scripted temperature
+    /// readings instead of real hardware, so the control loop can be
+    /// exercised off a Raspberry Pi.
+    dev_mode: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TempSourceConfig {
+    /// Reads via `vcgencmd measure_temp`.
+    Vcgencmd,
+
+    /// Reads the millidegree-Celsius integer at
+    /// `/sys/class/thermal/thermal_zone<zone>/temp`.
+    ThermalZone { zone: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum ControlMode {
+    /// Step through an ordered list of `(temperature, duty_cycle)` points.
+    /// The fan runs at the duty cycle of the highest step whose temperature
+    /// is at or below the current reading, and is off below the lowest step.
+    Stepped {
+        steps: Vec<FanStep>,
+
+        /// A step is only dropped once the temperature falls this many
+        /// degrees below that step's threshold, to avoid oscillating at a
+        /// boundary.
+        step_down_margin: f32,
+
+        /// A step change is only acted on once the temperature has
+        /// supported it for this many consecutive samples, so a single
+        /// noisy reading can't flip the fan.
+        consecutive_samples: u32,
+    },
+
+    /// Drive the duty cycle from a PID loop held against `target`, for a
+    /// smoother response than threshold stepping.
+    Pid { parameters: Parameters, target: f32 },
+}
+
+impl Default for ControlMode {
+    fn default() -> Self {
+        Self::Stepped {
+            steps: vec![
+                FanStep {
+                    temperature: 50.0,
+                    duty_cycle: 0.30,
+                },
+                FanStep {
+                    temperature: 60.0,
+                    duty_cycle: 0.55,
+                },
+                FanStep {
+                    temperature: 70.0,
+                    duty_cycle: 0.80,
+                },
+                FanStep {
+                    temperature: 80.0,
+                    duty_cycle: 1.0,
+                },
+            ],
+            step_down_margin: 3.0,
+            consecutive_samples: 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+struct FanStep {
+    /// The temperature at or above which this step applies.
+    temperature: f32,
 
-    /// The temperature below which the fan is turned off.
-    off_threshold: f32,
+    /// The fan PWM duty cycle for this step, in the range `0.0..=1.0`.
+    duty_cycle: f64,
 }
 
 impl Default for Config {
@@ -26,8 +135,14 @@ impl Default for Config {
         Self {
             gpio_pin: 17,
             interval: 15,
-            on_threshold: 60.0,
-            off_threshold: 50.0,
+            control_mode: ControlMode::default(),
+            temp_sources: vec![TempSourceConfig::Vcgencmd],
+            temp_aggregation: Aggregation::default(),
+            critical_threshold: 85.0,
+            critical_recovery_threshold: 75.0,
+            critical_samples: 3,
+            critical_action: String::new(),
+            dev_mode: false,
         }
     }
 }
@@ -51,31 +166,82 @@ impl Config {
             self.interval = Self::default().interval;
         }
 
-        let Self {
-            on_threshold,
-            off_threshold,
-            ..
-        } = self;
+        if self.temp_sources.is_empty() {
+            self.temp_sources = Self::default().temp_sources;
+        }
 
-        if off_threshold >= on_threshold {
-            return Err(ConfigError::InvalidThresholdRange {
-                off_threshold,
-                on_threshold,
+        if self.critical_recovery_threshold >= self.critical_threshold {
+            return Err(ConfigError::InvalidCriticalThresholdRange {
+                critical_recovery_threshold: self.critical_recovery_threshold,
+                critical_threshold: self.critical_threshold,
             });
         }
 
+        if self.critical_samples == 0 {
+            self.critical_samples = 1;
+        }
+
+        self.control_mode = match self.control_mode {
+            ControlMode::Stepped {
+                mut steps,
+                step_down_margin,
+                mut consecutive_samples,
+            } => {
+                if steps.is_empty() {
+                    steps = match ControlMode::default() {
+                        ControlMode::Stepped { steps, .. } => steps,
+                        ControlMode::Pid { .. } => unreachable!(),
+                    };
+                }
+
+                if !steps.windows(2).all(|w| w[0].temperature < w[1].temperature) {
+                    return Err(ConfigError::UnorderedSteps(steps));
+                }
+
+                if steps.iter().any(|step| !(0.0..=1.0).contains(&step.duty_cycle)) {
+                    return Err(ConfigError::InvalidDutyCycle(steps));
+                }
+
+                if step_down_margin < 0.0 {
+                    return Err(ConfigError::InvalidStepDownMargin(step_down_margin));
+                }
+
+                if consecutive_samples == 0 {
+                    consecutive_samples = 1;
+                }
+
+                ControlMode::Stepped {
+                    steps,
+                    step_down_margin,
+                    consecutive_samples,
+                }
+            }
+            ControlMode::Pid { parameters, target } => ControlMode::Pid {
+                parameters: parameters.validated()?,
+                target,
+            },
+        };
+
         Ok(self)
     }
 }
 
 #[derive(Error, Debug)]
 enum ConfigError {
-    #[error("`off_threshold` must be less than `on_threshold`, but is {off_threshold} and {on_threshold} respectively")]
-    InvalidThresholdRange {
-        off_threshold: f32,
-        on_threshold: f32,
+    #[error("`steps` must be in strictly ascending order of temperature, but is {0:?}")]
+    UnorderedSteps(Vec<FanStep>),
+    #[error("each step's `duty_cycle` must be in the range 0.0..=1.0, but `steps` is {0:?}")]
+    InvalidDutyCycle(Vec<FanStep>),
+    #[error("`step_down_margin` must not be negative, but is {0}")]
+    InvalidStepDownMargin(f32),
+    #[error("`critical_recovery_threshold` must be less than `critical_threshold`, but is {critical_recovery_threshold} and {critical_threshold} respectively")]
+    InvalidCriticalThresholdRange {
+        critical_recovery_threshold: f32,
+        critical_threshold: f32,
     },
     #[error("{0}")]
+    InvalidParameters(#[from] ParametersError),
+    #[error("{0}")]
     ConfyError(#[from] ConfyError),
 }
 
@@ -83,83 +249,217 @@ enum ConfigError {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let Config {
         interval: period,
-        on_threshold,
-        off_threshold,
+        control_mode,
         gpio_pin,
+        temp_sources,
+        temp_aggregation,
+        critical_threshold,
+        critical_recovery_threshold,
+        critical_samples,
+        critical_action,
+        dev_mode,
     } = Config::load()?;
 
     let mut interval = time::interval(Duration::from_secs(period));
-    let mut fan_pin = Gpio::new()?.get(gpio_pin)?.into_output();
+
+    let mut fan: Box<dyn FanControl> = if dev_mode {
+        Box::new(FakeFan)
+    } else {
+        Box::new(GpioFan::new(gpio_pin)?)
+    };
+    let mut temp_source: Box<dyn TempSource> = if dev_mode {
+        Box::new(FakeSensor::new(vec![45.0, 55.0, 65.0, 75.0, 85.0]))
+    } else {
+        let sources = temp_sources
+            .iter()
+            .map(|source| -> Box<dyn TempSource> {
+                match source {
+                    TempSourceConfig::Vcgencmd => Box::new(VcgencmdSensor),
+                    TempSourceConfig::ThermalZone { zone } => Box::new(ThermalZoneSensor::new(*zone)),
+                }
+            })
+            .collect();
+
+        Box::new(AggregateSensor::new(sources, temp_aggregation))
+    };
 
     print!(
-        "⚙️ Monitoring cpu temperature with `vcgencmd measure_temp` every {} seconds (INTERVAL environment variable).\n",
-        period
-    );
-    print!(
-        "Turns on fan if over {}'C (ON_THRESHOLD env), off if below {}'C (OFF_THRESHOLD env).\n",
-        on_threshold, off_threshold
+        "⚙️ Monitoring cpu temperature every {} seconds (`interval` config option) from {:?}.\n",
+        period, temp_sources
     );
-    println!(
-        "Using GPIO pin {} to control the fan (GPIO_PIN env).",
-        gpio_pin
+    if dev_mode {
+        println!("Running in dev mode: using a fake fan and scripted temperature readings.");
+    } else {
+        println!(
+            "Using GPIO pin {} to drive the fan via software PWM.",
+            gpio_pin
+        );
+    }
+
+    let mut thermal = ThermalStateMachine::new(
+        critical_threshold,
+        critical_recovery_threshold,
+        critical_samples,
     );
 
-    let mut do_if_overheat_change_exceeds_value = {
-        let mut last_overheat_amount: Option<f32> = None;
+    match control_mode {
+        ControlMode::Stepped {
+            steps,
+            step_down_margin,
+            consecutive_samples,
+        } => {
+            run_stepped(
+                &mut interval,
+                &mut *fan,
+                &mut *temp_source,
+                &steps,
+                step_down_margin,
+                consecutive_samples,
+                &mut thermal,
+                &critical_action,
+            )
+            .await
+        }
+        ControlMode::Pid { parameters, target } => {
+            run_pid(
+                &mut interval,
+                &mut *fan,
+                &mut *temp_source,
+                Controller::new(parameters, target),
+                &mut thermal,
+                &critical_action,
+            )
+            .await
+        }
+    }
+}
 
-        move |temp: f32, max_change: f32, f: fn(f32)| {
-            let is_not_overheating = temp < on_threshold;
+/// Reports a [`ThermalState`] transition and, when escalating into
+/// [`ThermalState::CriticalAlarm`], forces the fan to full speed and fires
+/// `critical_action` as a last-ditch safety backstop.
+fn handle_thermal_transition(
+    prev: ThermalState,
+    state: ThermalState,
+    temp: f32,
+    fan: &mut dyn FanControl,
+    critical_action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if state == prev {
+        return Ok(());
+    }
 
-            if is_not_overheating {
-                if last_overheat_amount.is_some() {
-                    last_overheat_amount = None;
-                }
-                return;
-            }
+    match state {
+        ThermalState::Overheat => println!("🥵: {}'C above the critical threshold", temp),
+        ThermalState::CriticalAlarm => {
+            println!("🚨: {}'C stayed critical, running critical action", temp);
+            fan.on()?;
+            run_critical_action(critical_action)?;
+        }
+        ThermalState::Normal if prev == ThermalState::CriticalAlarm => {
+            println!("✅: {}'C, recovered from the critical alarm", temp);
+        }
+        _ => {}
+    }
 
-            let overheat_amount = temp - on_threshold;
-            let overheat_change = last_overheat_amount.map(|v| (v - overheat_amount).abs());
-            let exceeded_max_change = overheat_change.filter(|v| *v <= max_change).is_none();
+    Ok(())
+}
 
-            if exceeded_max_change {
-                last_overheat_amount = Some(overheat_amount);
-                f(temp);
-            }
-        }
+fn run_critical_action(command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut parts = command.split_whitespace();
+
+    let Some(program) = parts.next() else {
+        return Ok(());
     };
 
+    Command::new(program).args(parts).spawn()?;
+
+    Ok(())
+}
+
+async fn run_stepped(
+    interval: &mut time::Interval,
+    fan: &mut dyn FanControl,
+    temp_source: &mut dyn TempSource,
+    steps: &[FanStep],
+    step_down_margin: f32,
+    consecutive_samples: u32,
+    thermal: &mut ThermalStateMachine,
+    critical_action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Stepping fan duty cycle over {} steps: {:?}", steps.len(), steps);
+
+    let mut current_step: Option<usize> = None;
+    let mut target_debounce = Debounce::new(consecutive_samples);
+
     loop {
         interval.tick().await;
 
-        let temp = read_temperature()?;
+        let temp = temp_source.read()?;
+        let raw_target_step = steps.iter().rposition(|step| step.temperature <= temp);
+
+        // Only consider a target once it's held for enough consecutive
+        // samples; until then, keep acting as if nothing changed.
+        let target_step = target_debounce.observe(raw_target_step).unwrap_or(current_step);
+
+        let new_step = match current_step {
+            Some(cur) if target_step.map_or(true, |target| target < cur) => {
+                // Temperature dropped below the current step's threshold; only
+                // actually step down once it clears the hysteresis margin.
+                if temp < steps[cur].temperature - step_down_margin {
+                    target_step
+                } else {
+                    Some(cur)
+                }
+            }
+            _ => target_step,
+        };
 
-        if fan_pin.is_set_low() && temp > on_threshold {
-            fan_pin.set_high();
-        } else if fan_pin.is_set_high() && temp < off_threshold {
-            fan_pin.set_low();
+        if new_step != current_step {
+            match new_step {
+                Some(i) => {
+                    fan.set_speed(steps[i].duty_cycle)?;
+                    println!(
+                        "🌀: {}'C, duty cycle {:.0}%",
+                        temp,
+                        steps[i].duty_cycle * 100.0
+                    );
+                }
+                None => {
+                    fan.off()?;
+                    println!("😌: {}'C", temp);
+                }
+            }
 
-            println!("😌: {}'C", temp);
+            current_step = new_step;
         }
 
-        do_if_overheat_change_exceeds_value(temp, 5.0, |t| println!("🥵: {}'C", t));
+        let prev_thermal_state = thermal.state();
+        let thermal_state = thermal.update(temp, new_step.is_some());
+        handle_thermal_transition(prev_thermal_state, thermal_state, temp, fan, critical_action)?;
     }
 }
 
-#[derive(Error, Debug)]
-enum ReadTempError {
-    #[error("reading failed: {0}")]
-    CommandOutputError(#[from] std::io::Error),
-    #[error("expected format is `temp=<num>'C\\n`, instead is `{0}`")]
-    ParseError(String),
-}
+async fn run_pid(
+    interval: &mut time::Interval,
+    fan: &mut dyn FanControl,
+    temp_source: &mut dyn TempSource,
+    mut controller: Controller,
+    thermal: &mut ThermalStateMachine,
+    critical_action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Holding temperature with a PID-controlled fan duty cycle.");
 
-fn read_temperature() -> Result<f32, ReadTempError> {
-    let output = Command::new("vcgencmd").arg("measure_temp").output()?;
-    let output = String::from_utf8_lossy(&output.stdout);
+    loop {
+        interval.tick().await;
 
-    let temp_str = &output["temp=".len()..(output.len() - "'C\n".len())];
+        let temp = temp_source.read()?;
+        let duty_cycle = controller.update(temp) as f64;
 
-    temp_str
-        .parse()
-        .map_err(|_| ReadTempError::ParseError(output.into_owned()))
+        fan.set_speed(duty_cycle)?;
+        println!("🌀: {}'C, duty cycle {:.0}%", temp, duty_cycle * 100.0);
+
+        let prev_thermal_state = thermal.state();
+        let thermal_state = thermal.update(temp, duty_cycle > 0.0);
+        handle_thermal_transition(prev_thermal_state, thermal_state, temp, fan, critical_action)?;
+    }
 }
@@ -0,0 +1,118 @@
+/// Coarse thermal state driving both the fan and the console logging, on top
+/// of whatever duty cycle the active [`crate::ControlMode`] computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThermalState {
+    /// The fan is off; nothing needs cooling.
+    Off,
+    /// The fan is running to keep the temperature in check.
+    Normal,
+    /// Temperature is above `critical_threshold` but hasn't been sustained
+    /// long enough yet to fire the critical action.
+    Overheat,
+    /// Temperature stayed above `critical_threshold` for long enough; the
+    /// critical action has fired.
+    CriticalAlarm,
+}
+
+/// Tracks [`ThermalState`] transitions, requiring the temperature to stay
+/// above `critical_threshold` for `required_samples` consecutive ticks
+/// before escalating to [`ThermalState::CriticalAlarm`], and only recovering
+/// once it cools below `recovery_threshold`.
+pub struct ThermalStateMachine {
+    state: ThermalState,
+    critical_threshold: f32,
+    recovery_threshold: f32,
+    required_samples: u32,
+    over_count: u32,
+}
+
+impl ThermalStateMachine {
+    pub fn new(critical_threshold: f32, recovery_threshold: f32, required_samples: u32) -> Self {
+        Self {
+            state: ThermalState::Off,
+            critical_threshold,
+            recovery_threshold,
+            required_samples: required_samples.max(1),
+            over_count: 0,
+        }
+    }
+
+    pub fn state(&self) -> ThermalState {
+        self.state
+    }
+
+    /// Feeds the latest temperature reading and whether the fan is currently
+    /// being driven (as opposed to idle), returning the resulting state.
+    pub fn update(&mut self, temp: f32, fan_active: bool) -> ThermalState {
+        if self.state == ThermalState::CriticalAlarm {
+            if temp < self.recovery_threshold {
+                self.over_count = 0;
+                self.state = ThermalState::Normal;
+            }
+
+            return self.state;
+        }
+
+        if temp > self.critical_threshold {
+            self.over_count += 1;
+
+            self.state = if self.over_count >= self.required_samples {
+                ThermalState::CriticalAlarm
+            } else {
+                ThermalState::Overheat
+            };
+        } else {
+            self.over_count = 0;
+            self.state = if fan_active {
+                ThermalState::Normal
+            } else {
+                ThermalState::Off
+            };
+        }
+
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_off_and_normal_below_critical() {
+        let mut thermal = ThermalStateMachine::new(80.0, 70.0, 3);
+
+        assert_eq!(thermal.update(50.0, false), ThermalState::Off);
+        assert_eq!(thermal.update(50.0, true), ThermalState::Normal);
+    }
+
+    #[test]
+    fn escalates_to_critical_only_after_required_samples() {
+        let mut thermal = ThermalStateMachine::new(80.0, 70.0, 3);
+
+        assert_eq!(thermal.update(90.0, true), ThermalState::Overheat);
+        assert_eq!(thermal.update(90.0, true), ThermalState::Overheat);
+        assert_eq!(thermal.update(90.0, true), ThermalState::CriticalAlarm);
+    }
+
+    #[test]
+    fn resets_overheat_streak_on_a_cooler_reading() {
+        let mut thermal = ThermalStateMachine::new(80.0, 70.0, 3);
+
+        assert_eq!(thermal.update(90.0, true), ThermalState::Overheat);
+        assert_eq!(thermal.update(90.0, true), ThermalState::Overheat);
+        // A single reading back under the critical threshold resets the streak.
+        assert_eq!(thermal.update(75.0, true), ThermalState::Normal);
+        assert_eq!(thermal.update(90.0, true), ThermalState::Overheat);
+    }
+
+    #[test]
+    fn stays_critical_until_below_recovery_threshold() {
+        let mut thermal = ThermalStateMachine::new(80.0, 70.0, 1);
+
+        assert_eq!(thermal.update(90.0, true), ThermalState::CriticalAlarm);
+        // Above recovery but below critical: still alarmed.
+        assert_eq!(thermal.update(75.0, true), ThermalState::CriticalAlarm);
+        assert_eq!(thermal.update(65.0, true), ThermalState::Normal);
+    }
+}